@@ -1,22 +1,34 @@
-use std::convert::Infallible;
+use std::convert::{Infallible, TryInto};
+use std::collections::HashMap;
 use std::fs;
+use std::io::{Read, Seek, SeekFrom};
 use std::net::{Ipv4Addr, SocketAddr};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::future::Future;
+use std::pin::Pin;
 use std::result::Result as StdResult;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::env;
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use crc32fast::Hasher as Crc32Hasher;
 use crossbeam::channel as cb_channel;
 use futures::future;
+use futures::stream::{FuturesUnordered, Stream};
 use hyper::{
-    server::conn::AddrIncoming, service::Service, Body, Request, Response, Server, StatusCode,
+    server::accept::Accept, service::Service, Body, Client, Request, Response, Server, StatusCode,
 };
+use hyper_rustls::HttpsConnector;
 use log::info;
+use parking_lot::RwLock;
 use rand::Rng;
 use serde_derive::{Deserialize, Serialize};
 use thiserror::Error;
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
 use uuid::Uuid;
 
 type Result<T> = StdResult<T, ShuffleManagerError>;
@@ -28,6 +40,10 @@ pub(crate) struct ShuffleManager {
     local_dir: PathBuf,
     shuffle_dir: PathBuf,
     server_uri: String,
+    /// Peer-discovered block locations, gossiped in over `/blocks`. Node-local
+    /// state, never sent across the wire with the rest of `ShuffleManager`.
+    #[serde(skip)]
+    registry: Arc<LocationRegistry>,
 }
 
 impl ShuffleManager {
@@ -36,11 +52,14 @@ impl ShuffleManager {
         let shuffle_dir = local_dir.join("shuffle");
         fs::create_dir_all(&shuffle_dir);
         let shuffle_port = env::Configuration::get().shuffle_svc_port;
-        let server_uri = ShuffleManager::start_server(shuffle_port)?;
+        let registry = Arc::new(LocationRegistry::default());
+        let server_uri =
+            ShuffleManager::start_server(shuffle_port, shuffle_dir.clone(), registry.clone())?;
         Ok(ShuffleManager {
             local_dir,
             shuffle_dir,
             server_uri,
+            registry,
         })
     }
 
@@ -48,37 +67,83 @@ impl ShuffleManager {
         self.server_uri.clone()
     }
 
+    /// Peers the local registry has gossiped as holding `(shuffle_id,
+    /// input_id, reduce_id)`, with a replica on the same host as this node
+    /// ordered first; within each group, peers keep the order in which their
+    /// latest advertisement was merged, oldest first. Lets a reducer pick a source without
+    /// asking the driver, and fall back to the next holder if one 404s.
+    pub fn locate_block(&self, shuffle_id: usize, input_id: usize, reduce_id: usize) -> Vec<String> {
+        self.registry
+            .locate((shuffle_id, input_id, reduce_id), gossip_ttl(), &self.server_uri)
+    }
+
     pub fn get_output_file(&self, shuffle_id: usize, input_id: usize, output_id: usize) -> String {
-        let path = self
-            .shuffle_dir
-            .join(format!("/{}/{}", shuffle_id, input_id));
-        fs::create_dir_all(&path);
-        let file_path = path.join(format!("{}", output_id));
+        let file_path = block_path(&self.shuffle_dir, shuffle_id, input_id, output_id);
+        fs::create_dir_all(file_path.parent().unwrap());
         fs::File::create(&file_path);
         file_path.to_str().unwrap().to_owned()
     }
 
+    /// Computes a CRC32 of a just-written block and stores it alongside the
+    /// block so `ShuffleService` can detect corruption before serving it.
+    /// When `env::Configuration` carries a job encryption key, the block is
+    /// then encrypted in place so it never sits on disk as plaintext.
+    /// Must be called once the writer is done with the file returned by
+    /// `get_output_file`.
+    pub fn finalize_output_file(
+        &self,
+        shuffle_id: usize,
+        input_id: usize,
+        output_id: usize,
+    ) -> Result<()> {
+        let path = block_path(&self.shuffle_dir, shuffle_id, input_id, output_id);
+        let plaintext =
+            fs::read(&path).map_err(|_| ShuffleManagerError::RequestedCacheNotFound)?;
+        let crc = compute_crc32(&plaintext[..]).map_err(|_| ShuffleManagerError::RequestedCacheNotFound)?;
+        fs::write(checksum_path(&path), crc.to_le_bytes())
+            .map_err(|_| ShuffleManagerError::ChecksumWriteFailed)?;
+
+        if let Some(key) = env::Configuration::get().shuffle_encryption_key {
+            let encrypted = encrypt_block(&key, &plaintext)?;
+            fs::write(&path, encrypted).map_err(|_| ShuffleManagerError::EncryptionFailed)?;
+        }
+        Ok(())
+    }
+
     /// Returns the shuffle server URI as a string.
-    fn start_server(port: Option<u16>) -> Result<String> {
+    fn start_server(
+        port: Option<u16>,
+        shuffle_dir: PathBuf,
+        registry: Arc<LocationRegistry>,
+    ) -> Result<String> {
         let bind_ip = env::Configuration::get().local_ip.clone();
+        // Load (and validate) the TLS config once up front: the advertised
+        // scheme below must match what `launch_async_runtime` actually binds,
+        // never what `tls_cert_path` merely suggests was intended.
+        let tls_config = ShuffleManager::load_tls_config()?;
+        let tls_enabled = tls_config.is_some();
         let port = if let Some(bind_port) = port {
-            let mut rt = tokio::runtime::Builder::new()
-                .enable_all()
-                .threaded_scheduler()
-                .build()
-                .map_err(|_| ShuffleManagerError::FailedToStart)?;
-            ShuffleManager::launch_async_runtime(rt, bind_ip, bind_port)?;
+            ShuffleManager::launch_async_runtime(
+                bind_ip,
+                bind_port,
+                shuffle_dir.clone(),
+                registry.clone(),
+                tls_config.clone(),
+            )?;
             bind_port
         } else {
             let mut port = 0;
             for retry in 0..10 {
                 let bind_port = get_dynamic_port();
-                let mut rt = tokio::runtime::Builder::new()
-                    .enable_all()
-                    .threaded_scheduler()
-                    .build()
-                    .map_err(|_| ShuffleManagerError::FailedToStart)?;
-                if let Ok(server) = ShuffleManager::launch_async_runtime(rt, bind_ip, bind_port) {
+                if ShuffleManager::launch_async_runtime(
+                    bind_ip,
+                    bind_port,
+                    shuffle_dir.clone(),
+                    registry.clone(),
+                    tls_config.clone(),
+                )
+                .is_ok()
+                {
                     port = bind_port;
                     break;
                 } else if retry == 9 {
@@ -87,37 +152,120 @@ impl ShuffleManager {
             }
             port
         };
-        let server_uri = format!(
-            "http://{}:{}",
-            env::Configuration::get().local_ip.clone(),
-            port,
-        );
+        let server_uri = format_server_uri(bind_ip, port, tls_enabled);
         log::debug!("server_uri {:?}", server_uri);
         Ok(server_uri)
     }
 
+    /// Loads the TLS cert/key pair configured in `env::Configuration`, if
+    /// any. Plaintext remains the default so single-host runs are unaffected,
+    /// but a half-configured pair (only one of cert/key set) is a
+    /// misconfiguration, not "TLS disabled" — it fails loudly rather than
+    /// silently falling back to plaintext while callers keep advertising
+    /// `https://`.
+    fn load_tls_config() -> Result<Option<Arc<rustls::ServerConfig>>> {
+        let conf = env::Configuration::get();
+        let (cert_path, key_path) = match (&conf.tls_cert_path, &conf.tls_key_path) {
+            (Some(cert_path), Some(key_path)) => (cert_path, key_path),
+            (None, None) => return Ok(None),
+            _ => return Err(ShuffleManagerError::TlsConfig),
+        };
+
+        let cert_file = fs::File::open(cert_path).map_err(|_| ShuffleManagerError::TlsConfig)?;
+        let certs = rustls::internal::pemfile::certs(&mut std::io::BufReader::new(cert_file))
+            .map_err(|_| ShuffleManagerError::TlsConfig)?;
+
+        let key_file = fs::File::open(key_path).map_err(|_| ShuffleManagerError::TlsConfig)?;
+        let mut keys =
+            rustls::internal::pemfile::pkcs8_private_keys(&mut std::io::BufReader::new(key_file))
+                .map_err(|_| ShuffleManagerError::TlsConfig)?;
+        let key = keys.pop().ok_or(ShuffleManagerError::TlsConfig)?;
+
+        let mut config = rustls::ServerConfig::new(rustls::NoClientAuth::new());
+        config
+            .set_single_cert(certs, key)
+            .map_err(|_| ShuffleManagerError::TlsConfig)?;
+        Ok(Some(Arc::new(config)))
+    }
+
+    /// Binds `shuffle_accept_threads()` `SO_REUSEPORT` sockets on `bind_port`
+    /// and runs each on its own tokio runtime/thread serving `ShuffleSvcMaker`,
+    /// so the kernel load-balances concurrent block fetches across accept
+    /// loops instead of funnelling them through a single acceptor.
     fn launch_async_runtime(
-        mut rt: tokio::runtime::Runtime,
         bind_ip: Ipv4Addr,
         bind_port: u16,
+        shuffle_dir: PathBuf,
+        registry: Arc<LocationRegistry>,
+        tls_config: Option<Arc<rustls::ServerConfig>>,
     ) -> Result<()> {
-        let (s, r) = cb_channel::bounded::<StdResult<(), hyper::error::Error>>(1);
-        thread::spawn(move || {
-            if let Err(err) = rt.block_on(async {
-                let bind_addr = SocketAddr::from((bind_ip, bind_port));
-                let server = Server::try_bind(&bind_addr.clone())
-                    .map_err(|_| ShuffleManagerError::FreePortNotFound(bind_port))
-                    .unwrap();
-                let server = server.serve(ShuffleSvcMaker);
-                server.await
-            }) {
-                s.send(Err(err));
-            };
-        });
+        let tls_enabled = tls_config.is_some();
+        let server_uri = format_server_uri(bind_ip, bind_port, tls_enabled);
+        let bind_addr = SocketAddr::from((bind_ip, bind_port));
+        let num_threads = shuffle_accept_threads();
+        let listeners = bind_reuseport(bind_addr, num_threads)
+            .map_err(|_| ShuffleManagerError::FailedToStart)?;
+
+        let (s, r) = cb_channel::bounded::<StdResult<(), hyper::error::Error>>(num_threads);
+        for (i, listener) in listeners.into_iter().enumerate() {
+            let shuffle_dir = shuffle_dir.clone();
+            let server_uri = server_uri.clone();
+            let tls_config = tls_config.clone();
+            let registry = registry.clone();
+            let s = s.clone();
+            thread::spawn(move || {
+                let mut rt = match tokio::runtime::Builder::new()
+                    .enable_all()
+                    .threaded_scheduler()
+                    .build()
+                {
+                    Ok(rt) => rt,
+                    Err(_) => return,
+                };
+                if let Err(err) = rt.block_on(async move {
+                    // Only one accept thread needs to run the gossip loop; it
+                    // fills the same registry `ShuffleManager::locate_block`
+                    // reads from, regardless of which thread served it.
+                    if i == 0 {
+                        tokio::spawn(gossip_loop(registry, tls_enabled));
+                    }
+                    let incoming = ReusePortIncoming::from_std(listener)
+                        .map_err(|_| ShuffleManagerError::FailedToStart)
+                        .unwrap();
+                    match tls_config {
+                        Some(tls_config) => {
+                            let incoming = TlsIncoming {
+                                incoming,
+                                acceptor: TlsAcceptor::from(tls_config),
+                                accepting: FuturesUnordered::new(),
+                            };
+                            Server::builder(incoming)
+                                .serve(ShuffleSvcMaker {
+                                    shuffle_dir,
+                                    server_uri,
+                                })
+                                .await
+                        }
+                        None => {
+                            Server::builder(incoming)
+                                .serve(ShuffleSvcMaker {
+                                    shuffle_dir,
+                                    server_uri,
+                                })
+                                .await
+                        }
+                    }
+                }) {
+                    let _ = s.send(Err(err));
+                };
+            });
+        }
         cb_channel::select! {
             recv(r) -> msg => { msg.map_err(|_| ShuffleManagerError::FailedToStart)?; }
             // wait a prudential time to check that initialization is ok and the move on
-            default(Duration::from_millis(100)) => log::debug!("started shuffle server @ {}", bind_port),
+            default(Duration::from_millis(100)) => {
+                log::debug!("started shuffle server @ {} across {} accept threads", bind_port, num_threads);
+            }
         };
         Ok(())
     }
@@ -148,47 +296,608 @@ fn get_dynamic_port() -> u16 {
     FIRST_DYNAMIC_PORT + rand::thread_rng().gen_range(0, LAST_DYNAMIC_PORT - FIRST_DYNAMIC_PORT)
 }
 
-type ShuffleServer = Server<AddrIncoming, ShuffleSvcMaker>;
+/// Path on disk of the block produced by `(shuffle_id, input_id, output_id)`.
+///
+/// Shared between the writer side (`ShuffleManager::get_output_file`) and the
+/// reader side (`ShuffleService`) so the two never disagree on layout.
+fn block_path(shuffle_dir: &Path, shuffle_id: usize, input_id: usize, output_id: usize) -> PathBuf {
+    shuffle_dir
+        .join(shuffle_id.to_string())
+        .join(input_id.to_string())
+        .join(output_id.to_string())
+}
+
+/// Path of the sidecar CRC32 checksum for a block, written by
+/// `ShuffleManager::finalize_output_file`.
+fn checksum_path(block_path: &Path) -> PathBuf {
+    let mut name = block_path.as_os_str().to_owned();
+    name.push(".crc32");
+    PathBuf::from(name)
+}
+
+/// Reads a block's sidecar checksum, if one was written for it. Blocks
+/// written before checksums existed simply have none.
+fn read_checksum(block_path: &Path) -> Option<u32> {
+    let bytes = fs::read(checksum_path(block_path)).ok()?;
+    let bytes: [u8; 4] = bytes.try_into().ok()?;
+    Some(u32::from_le_bytes(bytes))
+}
+
+/// Computes the CRC32 of a reader's contents in `STREAM_CHUNK_SIZE` windows,
+/// the same incremental pattern used to stream a block out to a client.
+fn compute_crc32<R: Read>(mut reader: R) -> StdResult<u32, std::io::Error> {
+    let mut hasher = Crc32Hasher::new();
+    let mut buf = [0u8; STREAM_CHUNK_SIZE];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize())
+}
+
+/// Bytes read from disk per chunk of a streamed block response.
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Length in bytes of the random nonce prepended to an encrypted block.
+const NONCE_LEN: usize = 12;
+
+/// Encrypts a block with ChaCha20-Poly1305 under a fresh random nonce,
+/// returning `nonce || ciphertext || tag`. The nonce must never repeat for
+/// a given key, so a new one is drawn for every block written.
+fn encrypt_block(key: &[u8; 32], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let mut nonce_bytes = [0u8; NONCE_LEN];
+    rand::thread_rng().fill(&mut nonce_bytes);
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce_bytes), plaintext)
+        .map_err(|_| ShuffleManagerError::EncryptionFailed)?;
+    let mut out = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+    out.extend_from_slice(&nonce_bytes);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+/// Reverses `encrypt_block`, authenticating the tag before returning
+/// plaintext. A bad tag (tampering, wrong key, truncation) is always fatal —
+/// never treated as a partial read — so corruption can't masquerade as a
+/// short response.
+fn decrypt_block(key: &[u8; 32], data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() < NONCE_LEN {
+        return Err(ShuffleManagerError::DecryptionFailed);
+    }
+    let (nonce_bytes, ciphertext) = data.split_at(NONCE_LEN);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    cipher
+        .decrypt(Nonce::from_slice(nonce_bytes), ciphertext)
+        .map_err(|_| ShuffleManagerError::DecryptionFailed)
+}
+
+/// Number of concurrent accept loops to run for the shuffle server, each its
+/// own `SO_REUSEPORT` socket and tokio runtime/thread. Defaults to the CPU
+/// count, overridable via `env::Configuration`, so the kernel has as many
+/// acceptors to spread connections across as there are cores to serve them.
+fn shuffle_accept_threads() -> usize {
+    env::Configuration::get()
+        .shuffle_svc_threads
+        .unwrap_or_else(num_cpus::get)
+        .max(1)
+}
+
+/// Binds `count` sockets to `addr` with `SO_REUSEPORT`/`SO_REUSEADDR`, so the
+/// kernel load-balances accepted connections across them instead of funnelling
+/// every connection through a single acceptor. Binding is synchronous and
+/// all-or-nothing: the first failure drops every socket opened so far
+/// (closing their file descriptors) and returns the error, so a caller
+/// retrying on a different port is never left with a partially-bound group.
+fn bind_reuseport(addr: SocketAddr, count: usize) -> std::io::Result<Vec<std::net::TcpListener>> {
+    let domain = if addr.is_ipv4() {
+        socket2::Domain::ipv4()
+    } else {
+        socket2::Domain::ipv6()
+    };
+    let mut listeners = Vec::with_capacity(count);
+    for _ in 0..count {
+        let socket = socket2::Socket::new(domain, socket2::Type::stream(), Some(socket2::Protocol::tcp()))?;
+        socket.set_reuse_address(true)?;
+        socket.set_reuse_port(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(1024)?;
+        listeners.push(socket.into_tcp_listener());
+    }
+    Ok(listeners)
+}
+
+/// Wraps a single `SO_REUSEPORT` listener as a `hyper` `Accept`. `hyper`'s own
+/// `AddrIncoming` has no public constructor from a pre-bound socket, so this
+/// drives the accept loop straight off `tokio::net::TcpListener` instead.
+struct ReusePortIncoming {
+    listener: tokio::net::TcpListener,
+}
+
+impl ReusePortIncoming {
+    fn from_std(listener: std::net::TcpListener) -> std::io::Result<Self> {
+        Ok(ReusePortIncoming {
+            listener: tokio::net::TcpListener::from_std(listener)?,
+        })
+    }
+}
+
+impl Accept for ReusePortIncoming {
+    type Conn = tokio::net::TcpStream;
+    type Error = std::io::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Option<StdResult<Self::Conn, Self::Error>>> {
+        match self.listener.poll_accept(cx) {
+            Poll::Ready(Ok((stream, _addr))) => Poll::Ready(Some(Ok(stream))),
+            Poll::Ready(Err(err)) => Poll::Ready(Some(Err(err))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+type TlsHandshake<C> = Pin<Box<dyn Future<Output = std::io::Result<TlsStream<C>>> + Send>>;
+
+/// Wraps any plain TCP accept loop with a TLS handshake per connection,
+/// letting `hyper::Server` drive HTTPS the same way it drives HTTP over
+/// whichever `Accept` impl is feeding it connections. Accepted sockets are
+/// handshaked concurrently via `FuturesUnordered` so one slow client can't
+/// stall the others.
+struct TlsIncoming<I: Accept> {
+    incoming: I,
+    acceptor: TlsAcceptor,
+    accepting: FuturesUnordered<TlsHandshake<I::Conn>>,
+}
+
+impl<I> Accept for TlsIncoming<I>
+where
+    I: Accept<Error = std::io::Error> + Unpin,
+    I::Conn: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    type Conn = TlsStream<I::Conn>;
+    type Error = std::io::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Option<StdResult<Self::Conn, Self::Error>>> {
+        loop {
+            match Pin::new(&mut self.incoming).poll_accept(cx) {
+                Poll::Ready(Some(Ok(stream))) => {
+                    let accept = self.acceptor.accept(stream);
+                    self.accepting.push(Box::pin(accept));
+                }
+                // A raw accept() failure on one connection (e.g. the peer
+                // reset before the handshake) must not tear down this
+                // thread's whole serve loop — log it and keep accepting.
+                Poll::Ready(Some(Err(err))) => {
+                    log::debug!("shuffle tls accept error: {}", err);
+                }
+                Poll::Ready(None) => break,
+                Poll::Pending => break,
+            }
+        }
+        loop {
+            return match Pin::new(&mut self.accepting).poll_next(cx) {
+                Poll::Ready(Some(Ok(stream))) => Poll::Ready(Some(Ok(stream))),
+                // A single misbehaving client (plaintext request to the
+                // HTTPS port, a port scanner, a truncated ClientHello, ...)
+                // must not propagate out as an `Accept` error — drop it and
+                // keep serving the rest of this thread's connections.
+                Poll::Ready(Some(Err(err))) => {
+                    log::debug!("shuffle tls handshake failed: {}", err);
+                    continue;
+                }
+                Poll::Ready(None) | Poll::Pending => Poll::Pending,
+            };
+        }
+    }
+}
+
+/// Formats the externally-reachable URI for the shuffle server bound at
+/// `bind_ip:port`, with the scheme implied by whether TLS is configured.
+fn format_server_uri(bind_ip: Ipv4Addr, port: u16, tls_enabled: bool) -> String {
+    let scheme = if tls_enabled { "https" } else { "http" };
+    format!("{}://{}:{}", scheme, bind_ip, port)
+}
+
+/// Identity of a shuffle block: `(shuffle_id, input_id, reduce_id)`, matching
+/// the ids already parsed off `/shuffle/{shuffle_id}/{input_id}/{reduce_id}`.
+type BlockId = (usize, usize, usize);
+
+/// How often a node gossips with its configured peers.
+const GOSSIP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Default time a gossiped block location is trusted before it's pruned,
+/// used when `env::Configuration` doesn't override it.
+const DEFAULT_GOSSIP_TTL: Duration = Duration::from_secs(60);
+
+/// How long a gossiped block location is trusted before
+/// `LocationRegistry::locate` drops it, overridable via `env::Configuration`
+/// for clusters whose gossip rounds run slower than the default.
+fn gossip_ttl() -> Duration {
+    env::Configuration::get()
+        .shuffle_gossip_ttl_secs
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_GOSSIP_TTL)
+}
+
+/// The compact JSON advertisement exchanged between peers over `/blocks`:
+/// which blocks this node currently holds (on disk or in `env::shuffle_cache`)
+/// and the URI reducers can fetch them from.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct BlocksAdvertisement {
+    server_uri: String,
+    blocks: Vec<BlockId>,
+}
 
-struct ShuffleService;
+/// A peer's claim, as of `last_seen`, to hold a block — merged into
+/// `LocationRegistry` from gossip rounds.
+#[derive(Clone, Debug)]
+struct PeerRecord {
+    peer_uri: String,
+    last_seen: Instant,
+}
+
+/// Shared, merged view of which peers hold which shuffle blocks, built
+/// entirely from periodic gossip rather than centralized driver metadata.
+/// Stale entries are pruned on read using the caller-supplied TTL.
+#[derive(Default, Debug)]
+struct LocationRegistry {
+    locations: RwLock<HashMap<BlockId, Vec<PeerRecord>>>,
+}
+
+impl LocationRegistry {
+    /// Merges a peer's advertisement in, replacing any prior record for that
+    /// peer on each block it claims with a fresh `last_seen`.
+    fn merge(&self, advertisement: &BlocksAdvertisement) {
+        let mut locations = self.locations.write();
+        for block in &advertisement.blocks {
+            let peers = locations.entry(*block).or_default();
+            peers.retain(|peer| peer.peer_uri != advertisement.server_uri);
+            peers.push(PeerRecord {
+                peer_uri: advertisement.server_uri.clone(),
+                last_seen: Instant::now(),
+            });
+        }
+    }
+
+    /// Peers known to hold `block`, pruning any advertisement older than
+    /// `ttl`, with a same-host peer as `prefer_uri` (if any) ordered first.
+    fn locate(&self, block: BlockId, ttl: Duration, prefer_uri: &str) -> Vec<String> {
+        let mut locations = self.locations.write();
+        let peers = match locations.get_mut(&block) {
+            Some(peers) => peers,
+            None => return Vec::new(),
+        };
+        peers.retain(|peer| peer.last_seen.elapsed() < ttl);
+        let prefer_host = host_of(prefer_uri);
+        let mut uris: Vec<String> = peers.iter().map(|peer| peer.peer_uri.clone()).collect();
+        uris.sort_by_key(|uri| host_of(uri) != prefer_host);
+        uris
+    }
+}
+
+/// Host portion of a `scheme://host:port` server URI, used to prefer a
+/// same-host replica when more than one peer holds the same block.
+fn host_of(uri: &str) -> &str {
+    uri.split("://")
+        .nth(1)
+        .unwrap_or(uri)
+        .split(':')
+        .next()
+        .unwrap_or(uri)
+}
+
+/// Blocks this node currently holds: every key in `env::shuffle_cache` plus
+/// every `(shuffle_id, input_id, reduce_id)` with a file under `shuffle_dir`.
+fn local_block_ids(shuffle_dir: &Path) -> Vec<BlockId> {
+    let mut ids: Vec<BlockId> = env::shuffle_cache.read().keys().copied().collect();
+
+    let shuffle_ids = match fs::read_dir(shuffle_dir) {
+        Ok(entries) => entries,
+        Err(_) => return ids,
+    };
+    for shuffle_entry in shuffle_ids.filter_map(|entry| entry.ok()) {
+        let shuffle_id: usize = match shuffle_entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+            Some(id) => id,
+            None => continue,
+        };
+        let input_ids = match fs::read_dir(shuffle_entry.path()) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for input_entry in input_ids.filter_map(|entry| entry.ok()) {
+            let input_id: usize = match input_entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+                Some(id) => id,
+                None => continue,
+            };
+            let output_ids = match fs::read_dir(input_entry.path()) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for output_entry in output_ids.filter_map(|entry| entry.ok()) {
+                let name = output_entry.file_name();
+                let name = match name.to_str() {
+                    Some(name) => name,
+                    None => continue,
+                };
+                if name.ends_with(".crc32") {
+                    continue;
+                }
+                if let Ok(output_id) = name.parse() {
+                    ids.push((shuffle_id, input_id, output_id));
+                }
+            }
+        }
+    }
+    ids
+}
+
+/// Periodically pulls each configured peer's `/blocks` advertisement and
+/// merges it into `registry`, so reducers can discover map outputs through
+/// `ShuffleManager::locate_block` without the driver ever being involved.
+async fn gossip_loop(registry: Arc<LocationRegistry>, tls_enabled: bool) {
+    let peers = env::Configuration::get().shuffle_peers.clone();
+    if peers.is_empty() {
+        return;
+    }
+    // `/blocks` is served on the same hyper service as `/shuffle/...`, so
+    // gossip must speak whatever scheme that service is actually listening
+    // on — an HTTP-only client can never reach a TLS-enabled peer. `tls_enabled`
+    // reflects the TLS config this accept thread actually loaded, not just
+    // whether a cert path was configured.
+    let client = Client::builder().build(HttpsConnector::new());
+    let mut interval = tokio::time::interval(GOSSIP_INTERVAL);
+    loop {
+        interval.tick().await;
+        for peer in &peers {
+            gossip_with_peer(&client, &registry, *peer, tls_enabled).await;
+        }
+    }
+}
+
+/// Fetches `peer`'s `/blocks` advertisement and merges it into `registry`.
+/// Gossip is best-effort: an unreachable or misbehaving peer is simply
+/// skipped until the next round rather than treated as a fatal error.
+async fn gossip_with_peer(
+    client: &Client<HttpsConnector<hyper::client::HttpConnector>>,
+    registry: &LocationRegistry,
+    peer: SocketAddr,
+    tls_enabled: bool,
+) {
+    let scheme = if tls_enabled { "https" } else { "http" };
+    let uri: hyper::Uri = match format!("{}://{}/blocks", scheme, peer).parse() {
+        Ok(uri) => uri,
+        Err(_) => return,
+    };
+    let response = match client.get(uri).await {
+        Ok(response) => response,
+        Err(_) => return,
+    };
+    let body = match hyper::body::to_bytes(response.into_body()).await {
+        Ok(body) => body,
+        Err(_) => return,
+    };
+    if let Ok(advertisement) = serde_json::from_slice::<BlocksAdvertisement>(&body) {
+        registry.merge(&advertisement);
+    }
+}
+
+struct ShuffleService {
+    shuffle_dir: PathBuf,
+    server_uri: String,
+}
 
 enum ShuffleResponse {
     Status(StatusCode),
-    CachedData(Vec<u8>),
+    /// An in-memory cached block, sliced to the requested range just like
+    /// the disk-backed paths so a reducer's `Range` request is honored
+    /// regardless of whether the block happened to be cache-resident.
+    CachedData {
+        data: Vec<u8>,
+        total_len: u64,
+        range: Option<(u64, u64)>,
+        crc32: Option<u32>,
+    },
+    Block {
+        file: fs::File,
+        total_len: u64,
+        range: Option<(u64, u64)>,
+        crc32: Option<u32>,
+    },
+    /// An encrypted-at-rest block, already decrypted and (if requested)
+    /// sliced to the requested range. Held fully in memory: authenticating
+    /// an AEAD tag requires the whole ciphertext, so this path can't stream
+    /// in bounded chunks the way a plaintext block can — enabling encryption
+    /// knowingly regresses the bounded-memory Range guarantee every other
+    /// branch here provides. The response body is also plaintext, so this
+    /// only protects the block in transit when the server is also serving
+    /// over TLS; encryption-at-rest and TLS are independent knobs.
+    DecryptedBlock {
+        data: Vec<u8>,
+        total_len: u64,
+        range: Option<(u64, u64)>,
+        crc32: Option<u32>,
+    },
+    Blocks(BlocksAdvertisement),
 }
 
 impl ShuffleService {
-    fn response_type(&self, uri: &hyper::Uri) -> Result<ShuffleResponse> {
+    fn response_type(&self, req: &Request<Body>) -> Result<ShuffleResponse> {
+        let uri = req.uri().clone();
         let parts: Vec<_> = uri.path().split('/').collect();
         match parts.as_slice() {
             [_, endpoint] if *endpoint == "status" => Ok(ShuffleResponse::Status(StatusCode::OK)),
-            [_, endpoint, shuffle_id, input_id, reduce_id] if *endpoint == "shuffle" => Ok(
-                ShuffleResponse::CachedData(
-                    self.get_cached_data(uri, &[*shuffle_id, *input_id, *reduce_id])?,
-                ),
-            ),
+            [_, endpoint] if *endpoint == "blocks" => Ok(ShuffleResponse::Blocks(BlocksAdvertisement {
+                server_uri: self.server_uri.clone(),
+                blocks: local_block_ids(&self.shuffle_dir),
+            })),
+            [_, endpoint, shuffle_id, input_id, reduce_id] if *endpoint == "shuffle" => {
+                let ids = ShuffleService::parse_ids(&uri, &[*shuffle_id, *input_id, *reduce_id])?;
+                self.get_block(req, ids)
+            }
             _ => Err(ShuffleManagerError::FailedToParseUri(format!("{}", uri))),
         }
     }
 
-    fn get_cached_data(&self, uri: &hyper::Uri, parts: &[&str]) -> Result<Vec<u8>> {
-        // the path is: .../{shuffleid}/{inputid}/{reduceid}
-        let parts: Vec<_> = match parts
-            .iter()
-            .map(|part| ShuffleService::parse_path_part(part))
-            .collect::<Result<_>>()
+    /// Looks up a block, preferring the in-memory cache and falling back to
+    /// the file written by `ShuffleManager::get_output_file` on a miss.
+    fn get_block(&self, req: &Request<Body>, ids: (usize, usize, usize)) -> Result<ShuffleResponse> {
         {
-            Err(err) => {
-                return Err(ShuffleManagerError::FailedToParseUri(format!("{}", uri)));
+            let cache = env::shuffle_cache.read();
+            if let Some(cached_data) = cache.get(&ids) {
+                let total_len = cached_data.len() as u64;
+                let crc32 = compute_crc32(&cached_data[..]).ok();
+                let range = ShuffleService::parse_range_header(req, total_len)?;
+                let data = match range {
+                    Some((start, end)) => cached_data[start as usize..=end as usize].to_vec(),
+                    None => cached_data.clone(),
+                };
+                return Ok(ShuffleResponse::CachedData {
+                    data,
+                    total_len,
+                    range,
+                    crc32,
+                });
             }
-            Ok(parts) => parts,
+        }
+
+        let path = block_path(&self.shuffle_dir, ids.0, ids.1, ids.2);
+
+        if let Some(key) = env::Configuration::get().shuffle_encryption_key {
+            let ciphertext =
+                fs::read(&path).map_err(|_| ShuffleManagerError::RequestedCacheNotFound)?;
+            let plaintext = decrypt_block(&key, &ciphertext)?;
+            let total_len = plaintext.len() as u64;
+
+            let crc32 = ShuffleService::verify_checksum(&path, &plaintext)?;
+            let range = ShuffleService::parse_range_header(req, total_len)?;
+            let data = match range {
+                Some((start, end)) => plaintext[start as usize..=end as usize].to_vec(),
+                None => plaintext,
+            };
+
+            return Ok(ShuffleResponse::DecryptedBlock {
+                data,
+                total_len,
+                range,
+                crc32,
+            });
+        }
+
+        let mut file =
+            fs::File::open(&path).map_err(|_| ShuffleManagerError::RequestedCacheNotFound)?;
+        let total_len = file
+            .metadata()
+            .map_err(|_| ShuffleManagerError::RequestedCacheNotFound)?
+            .len();
+
+        let range = ShuffleService::parse_range_header(req, total_len)?;
+
+        // The stored checksum covers the whole block, so it's only meaningful
+        // on an unranged fetch — a partial fetch only ever streams a slice of
+        // the file below. Rather than reading the whole file here just to
+        // verify it and then reading it again to stream it, the expected
+        // checksum is handed to `stream_block`, which hashes each chunk as
+        // it's emitted and fails the stream if the running hash doesn't
+        // match once the last chunk has been read.
+        let crc32 = if range.is_none() {
+            read_checksum(&path)
+        } else {
+            None
         };
-        let cache = env::shuffle_cache.read();
-        if let Some(cached_data) = cache.get(&(parts[0], parts[1], parts[2])) {
-            Ok(Vec::from(&cached_data[..]))
+
+        let start = range.map(|(start, _)| start).unwrap_or(0);
+        file.seek(SeekFrom::Start(start))
+            .map_err(|_| ShuffleManagerError::RequestedCacheNotFound)?;
+
+        Ok(ShuffleResponse::Block {
+            file,
+            total_len,
+            range,
+            crc32,
+        })
+    }
+
+    /// Checks a decrypted block's plaintext against its stored CRC32, if any.
+    fn verify_checksum(path: &Path, plaintext: &[u8]) -> Result<Option<u32>> {
+        if let Some(expected) = read_checksum(path) {
+            let got = compute_crc32(plaintext).map_err(|_| ShuffleManagerError::RequestedCacheNotFound)?;
+            if got != expected {
+                return Err(ShuffleManagerError::ChecksumMismatch { expected, got });
+            }
+            Ok(Some(got))
         } else {
-            Err(ShuffleManagerError::RequestedCacheNotFound)
+            Ok(None)
+        }
+    }
+
+    /// Parses a `Range: bytes=start-end` header into an inclusive `(start, end)`
+    /// pair, clamped to `total_len`. Returns `Ok(None)` when no range was
+    /// requested and `Err(UnsatisfiableRange)` when the range can't be served.
+    fn parse_range_header(req: &Request<Body>, total_len: u64) -> Result<Option<(u64, u64)>> {
+        let header = match req.headers().get(hyper::header::RANGE) {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+        let header = header
+            .to_str()
+            .map_err(|_| ShuffleManagerError::UnsatisfiableRange)?;
+        let spec = header
+            .strip_prefix("bytes=")
+            .ok_or(ShuffleManagerError::UnsatisfiableRange)?;
+        let (start, end) = spec
+            .split_once('-')
+            .ok_or(ShuffleManagerError::UnsatisfiableRange)?;
+        if total_len == 0 {
+            return Err(ShuffleManagerError::UnsatisfiableRange);
+        }
+
+        let (start, end) = match (start, end) {
+            ("", "") => return Err(ShuffleManagerError::UnsatisfiableRange),
+            ("", suffix_len) => {
+                let suffix_len: u64 = suffix_len
+                    .parse()
+                    .map_err(|_| ShuffleManagerError::UnsatisfiableRange)?;
+                (total_len.saturating_sub(suffix_len), total_len - 1)
+            }
+            (start, "") => {
+                let start: u64 = start
+                    .parse()
+                    .map_err(|_| ShuffleManagerError::UnsatisfiableRange)?;
+                (start, total_len - 1)
+            }
+            (start, end) => {
+                let start: u64 = start
+                    .parse()
+                    .map_err(|_| ShuffleManagerError::UnsatisfiableRange)?;
+                let end: u64 = end
+                    .parse()
+                    .map_err(|_| ShuffleManagerError::UnsatisfiableRange)?;
+                (start, end)
+            }
+        };
+
+        if total_len == 0 || start > end || start >= total_len {
+            return Err(ShuffleManagerError::UnsatisfiableRange);
         }
+        Ok(Some((start, end.min(total_len - 1))))
+    }
+
+    fn parse_ids(uri: &hyper::Uri, parts: &[&str]) -> Result<(usize, usize, usize)> {
+        let parts: Vec<usize> = parts
+            .iter()
+            .map(|part| ShuffleService::parse_path_part(part))
+            .collect::<Result<_>>()
+            .map_err(|_| ShuffleManagerError::FailedToParseUri(format!("{}", uri)))?;
+        Ok((parts[0], parts[1], parts[2]))
     }
 
     #[inline]
@@ -198,6 +907,115 @@ impl ShuffleService {
     }
 }
 
+/// Streams `len` bytes starting at the file's current position in
+/// `STREAM_CHUNK_SIZE` windows instead of buffering the whole block.
+///
+/// When `expected_crc` is set (an unranged fetch of a block with a stored
+/// checksum), the block is hashed incrementally as it's read so the file is
+/// only ever read once, instead of one full pass to verify it and a second
+/// to stream it. A mismatch can't be signalled by appending anything after
+/// the last chunk — `Content-Length` has already committed the response to
+/// an exact byte count — so the last chunk is held back in a one-chunk
+/// lookahead buffer until it's known to be the last, and is only emitted if
+/// the running hash checks out; otherwise it's dropped, the stream ends
+/// short of the promised length, and the transfer itself fails rather than
+/// quietly handing the reducer a corrupt-but-"successful" block.
+fn stream_block(mut file: fs::File, mut len: u64, expected_crc: Option<u32>) -> Body {
+    let mut hasher = Crc32Hasher::new();
+    let mut lookahead: Option<Vec<u8>> = None;
+    let mut done = false;
+    let stream = futures::stream::unfold((), move |_| {
+        let item = loop {
+            if done {
+                break None;
+            }
+            let cur = lookahead.take();
+            if len == 0 {
+                done = true;
+                break cur.filter(|_| verify(&hasher, expected_crc)).map(Ok);
+            }
+            let to_read = std::cmp::min(len, STREAM_CHUNK_SIZE as u64) as usize;
+            let mut buf = vec![0u8; to_read];
+            match file.read(&mut buf) {
+                Ok(0) => {
+                    len = 0;
+                    if cur.is_none() {
+                        continue;
+                    }
+                    done = true;
+                    break cur.filter(|_| verify(&hasher, expected_crc)).map(Ok);
+                }
+                Ok(n) => {
+                    buf.truncate(n);
+                    len -= n as u64;
+                    if expected_crc.is_some() {
+                        hasher.update(&buf);
+                    }
+                    lookahead = Some(buf);
+                    match cur {
+                        Some(cur) => break Some(Ok::<_, std::io::Error>(cur)),
+                        None => continue,
+                    }
+                }
+                Err(err) => {
+                    done = true;
+                    break Some(Err(err));
+                }
+            }
+        };
+        future::ready(item.map(|item| (item, ())))
+    });
+    Body::wrap_stream(stream)
+}
+
+/// Whether the running hash of a fully-read block matches its expected
+/// checksum; always true when there was nothing to verify against.
+fn verify(hasher: &Crc32Hasher, expected_crc: Option<u32>) -> bool {
+    match expected_crc {
+        Some(expected) => hasher.clone().finalize() == expected,
+        None => true,
+    }
+}
+
+/// Builds the `200`/`206` response headers shared by the streamed and
+/// fully-buffered block response paths. `crc32` is the checksum of the
+/// *whole* block, so it's only meaningful (and only attached) on a full,
+/// unranged response — stamping it on a `206` would have a fetcher verify
+/// a slice against a whole-block checksum and always fail.
+///
+/// This header is only half of the integrity story: the shuffle fetch
+/// client that would read `X-Shuffle-CRC32`, compare it against the bytes
+/// it received, and retry from another replica on a mismatch lives outside
+/// this module and isn't part of this snapshot of the crate, so it isn't
+/// wired up here.
+fn range_response_builder(
+    total_len: u64,
+    range: Option<(u64, u64)>,
+    crc32: Option<u32>,
+) -> hyper::http::response::Builder {
+    match range {
+        Some((start, end)) => Response::builder()
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(
+                hyper::header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end, total_len),
+            )
+            .header(
+                hyper::header::CONTENT_LENGTH,
+                end - start + 1,
+            ),
+        None => {
+            let rsp = Response::builder()
+                .status(StatusCode::OK)
+                .header(hyper::header::CONTENT_LENGTH, total_len);
+            match crc32 {
+                Some(crc32) => rsp.header("X-Shuffle-CRC32", format!("{:08x}", crc32)),
+                None => rsp,
+            }
+        }
+    }
+}
+
 impl Service<Request<Body>> for ShuffleService {
     type Response = Response<Body>;
     type Error = ShuffleManagerError;
@@ -208,27 +1026,56 @@ impl Service<Request<Body>> for ShuffleService {
     }
 
     fn call(&mut self, req: Request<Body>) -> Self::Future {
-        match self.response_type(req.uri()) {
-            Ok(response) => match response {
-                ShuffleResponse::Status(code) => {
-                    let rsp = Response::builder();
-                    let body = Body::from(&[] as &[u8]);
-                    let rsp = rsp.status(code).body(body).unwrap();
-                    future::ok(rsp)
-                }
-                ShuffleResponse::CachedData(cached_data) => {
-                    let rsp = Response::builder();
-                    let body = Body::from(Vec::from(&cached_data[..]));
-                    let rsp = rsp.status(200).body(body).unwrap();
-                    future::ok(rsp)
-                }
-            },
+        match self.response_type(&req) {
+            Ok(ShuffleResponse::Status(code)) => {
+                let body = Body::from(&[] as &[u8]);
+                future::ok(Response::builder().status(code).body(body).unwrap())
+            }
+            Ok(ShuffleResponse::CachedData {
+                data,
+                total_len,
+                range,
+                crc32,
+            }) => {
+                let rsp = range_response_builder(total_len, range, crc32);
+                future::ok(rsp.body(Body::from(data)).unwrap())
+            }
+            Ok(ShuffleResponse::Block {
+                file,
+                total_len,
+                range,
+                crc32,
+            }) => {
+                let rsp = range_response_builder(total_len, range, crc32);
+                let len = range.map(|(start, end)| end - start + 1).unwrap_or(total_len);
+                future::ok(rsp.body(stream_block(file, len, crc32)).unwrap())
+            }
+            Ok(ShuffleResponse::DecryptedBlock {
+                data,
+                total_len,
+                range,
+                crc32,
+            }) => {
+                let rsp = range_response_builder(total_len, range, crc32);
+                future::ok(rsp.body(Body::from(data)).unwrap())
+            }
+            Ok(ShuffleResponse::Blocks(advertisement)) => {
+                let body = serde_json::to_vec(&advertisement).unwrap_or_default();
+                let rsp = Response::builder()
+                    .status(StatusCode::OK)
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .header(hyper::header::CONTENT_LENGTH, body.len());
+                future::ok(rsp.body(Body::from(body)).unwrap())
+            }
             Err(err) => future::ok(err.into()),
         }
     }
 }
 
-struct ShuffleSvcMaker;
+struct ShuffleSvcMaker {
+    shuffle_dir: PathBuf,
+    server_uri: String,
+}
 
 impl<T> Service<T> for ShuffleSvcMaker {
     type Response = ShuffleService;
@@ -240,7 +1087,10 @@ impl<T> Service<T> for ShuffleSvcMaker {
     }
 
     fn call(&mut self, _: T) -> Self::Future {
-        future::ok(ShuffleService)
+        future::ok(ShuffleService {
+            shuffle_dir: self.shuffle_dir.clone(),
+            server_uri: self.server_uri.clone(),
+        })
     }
 }
 
@@ -263,6 +1113,24 @@ pub enum ShuffleManagerError {
 
     #[error("not valid endpoint")]
     NotValidEndpoint,
+
+    #[error("requested range cannot be satisfied")]
+    UnsatisfiableRange,
+
+    #[error("checksum mismatch: expected {expected:08x}, got {got:08x}")]
+    ChecksumMismatch { expected: u32, got: u32 },
+
+    #[error("failed to write shuffle block checksum to disk")]
+    ChecksumWriteFailed,
+
+    #[error("failed to encrypt shuffle block")]
+    EncryptionFailed,
+
+    #[error("failed to decrypt shuffle block")]
+    DecryptionFailed,
+
+    #[error("failed to load TLS certificate/key for the shuffle server")]
+    TlsConfig,
 }
 
 impl Into<Response<Body>> for ShuffleManagerError {
@@ -280,6 +1148,17 @@ impl Into<Response<Body>> for ShuffleManagerError {
                 .status(StatusCode::NOT_FOUND)
                 .body(Body::from(&[] as &[u8]))
                 .unwrap(),
+            ShuffleManagerError::UnsatisfiableRange => Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .body(Body::from(&[] as &[u8]))
+                .unwrap(),
+            ShuffleManagerError::ChecksumMismatch { expected, got } => Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(format!(
+                    "checksum mismatch: expected {:08x}, got {:08x}",
+                    expected, got
+                )))
+                .unwrap(),
             _ => Response::builder()
                 .status(StatusCode::INTERNAL_SERVER_ERROR)
                 .body(Body::from(&[] as &[u8]))
@@ -291,7 +1170,6 @@ impl Into<Response<Body>> for ShuffleManagerError {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Read;
     use std::net::TcpListener;
     use std::time::Duration;
     use tokio::prelude::*;
@@ -307,10 +1185,14 @@ mod tests {
         panic!("failed to find free port while testing");
     }
 
+    fn test_shuffle_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("native_spark-test-{}", Uuid::new_v4()))
+    }
+
     #[test]
     fn start_ok() -> StdResult<(), Box<dyn std::error::Error + 'static>> {
         let port = get_free_port();
-        ShuffleManager::start_server(Some(port))?;
+        ShuffleManager::start_server(Some(port), test_shuffle_dir(), Arc::new(LocationRegistry::default()))?;
 
         let url = format!(
             "http://{}:{}/status",
@@ -328,7 +1210,7 @@ mod tests {
         // bind first so it fails while trying to start
         let bind = TcpListener::bind(format!("127.0.0.1:{}", port))?;
         assert_eq!(
-            ShuffleManager::start_server(Some(port)).unwrap_err(),
+            ShuffleManager::start_server(Some(port), test_shuffle_dir(), Arc::new(LocationRegistry::default())).unwrap_err(),
             ShuffleManagerError::FailedToStart
         );
         Ok(())
@@ -337,7 +1219,7 @@ mod tests {
     #[test]
     fn cached_data_found() -> StdResult<(), Box<dyn std::error::Error + 'static>> {
         let port = get_free_port();
-        ShuffleManager::start_server(Some(port))?;
+        ShuffleManager::start_server(Some(port), test_shuffle_dir(), Arc::new(LocationRegistry::default()))?;
         let data = b"some random bytes".iter().copied().collect::<Vec<u8>>();
         {
             let mut cache = env::shuffle_cache.write();
@@ -360,10 +1242,34 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn cached_data_served_with_range() -> StdResult<(), Box<dyn std::error::Error + 'static>> {
+        let port = get_free_port();
+        ShuffleManager::start_server(Some(port), test_shuffle_dir(), Arc::new(LocationRegistry::default()))?;
+        {
+            let mut cache = env::shuffle_cache.write();
+            cache.insert((6, 1, 0), b"0123456789".to_vec());
+        }
+        let client = reqwest::Client::new();
+        let url = format!(
+            "http://{}:{}/shuffle/6/1/0",
+            env::Configuration::get().local_ip,
+            port
+        );
+        let mut res = client.get(&url).header("Range", "bytes=2-5").send()?;
+        assert_eq!(res.status(), reqwest::StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            res.headers().get(reqwest::header::CONTENT_RANGE).unwrap(),
+            "bytes 2-5/10"
+        );
+        assert_eq!(res.text()?, "2345");
+        Ok(())
+    }
+
     #[test]
     fn cached_data_not_found() -> StdResult<(), Box<dyn std::error::Error + 'static>> {
         let port = get_free_port();
-        ShuffleManager::start_server(Some(port))?;
+        ShuffleManager::start_server(Some(port), test_shuffle_dir(), Arc::new(LocationRegistry::default()))?;
 
         let url = format!(
             "http://{}:{}/shuffle/0/1/2",
@@ -378,7 +1284,7 @@ mod tests {
     #[test]
     fn not_valid_endpoint() -> StdResult<(), Box<dyn std::error::Error + 'static>> {
         let port = get_free_port();
-        ShuffleManager::start_server(Some(port))?;
+        ShuffleManager::start_server(Some(port), test_shuffle_dir(), Arc::new(LocationRegistry::default()))?;
 
         let url = format!(
             "http://{}:{}/not_valid",
@@ -390,4 +1296,161 @@ mod tests {
         assert_eq!(res.text()?, format!("Failed to parse: /not_valid"));
         Ok(())
     }
+
+    #[test]
+    fn disk_block_served_with_range() -> StdResult<(), Box<dyn std::error::Error + 'static>> {
+        let port = get_free_port();
+        let shuffle_dir = test_shuffle_dir();
+        ShuffleManager::start_server(Some(port), shuffle_dir.clone(), Arc::new(LocationRegistry::default()))?;
+
+        let data = b"0123456789".to_vec();
+        let path = block_path(&shuffle_dir, 4, 1, 0);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, &data)?;
+
+        let client = reqwest::Client::new();
+        let base = format!(
+            "http://{}:{}/shuffle/4/1/0",
+            env::Configuration::get().local_ip,
+            port
+        );
+
+        let res = client.get(&base).send()?;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+        let mut res = client.get(&base).header("Range", "bytes=2-5").send()?;
+        assert_eq!(res.status(), reqwest::StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            res.headers()
+                .get(reqwest::header::CONTENT_RANGE)
+                .unwrap(),
+            "bytes 2-5/10"
+        );
+        assert_eq!(res.text()?, "2345");
+
+        let res = client.get(&base).header("Range", "bytes=100-200").send()?;
+        assert_eq!(res.status(), reqwest::StatusCode::RANGE_NOT_SATISFIABLE);
+
+        Ok(())
+    }
+
+    #[test]
+    fn disk_block_checksum_verified() -> StdResult<(), Box<dyn std::error::Error + 'static>> {
+        let port = get_free_port();
+        let shuffle_dir = test_shuffle_dir();
+        ShuffleManager::start_server(Some(port), shuffle_dir.clone(), Arc::new(LocationRegistry::default()))?;
+        let manager = ShuffleManager {
+            local_dir: shuffle_dir.clone(),
+            shuffle_dir: shuffle_dir.clone(),
+            server_uri: String::new(),
+            registry: Arc::new(LocationRegistry::default()),
+        };
+
+        let path = block_path(&shuffle_dir, 5, 1, 0);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, b"checksummed bytes")?;
+        manager.finalize_output_file(5, 1, 0)?;
+
+        let url = format!(
+            "http://{}:{}/shuffle/5/1/0",
+            env::Configuration::get().local_ip,
+            port
+        );
+        let res = reqwest::get(&url)?;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+        let crc32 = res
+            .headers()
+            .get("X-Shuffle-CRC32")
+            .unwrap()
+            .to_str()?
+            .to_owned();
+
+        // Corrupt the block in place; the stored checksum no longer matches.
+        // The checksum is now verified incrementally as the body streams, so
+        // the `200` and `Content-Length` are already committed by the time
+        // the mismatch is found — the last chunk is withheld instead, and
+        // the transfer itself fails short of the promised length.
+        fs::write(&path, b"tampered bytes!!!!")?;
+        assert!(reqwest::get(&url).is_err());
+        assert_ne!(crc32, "");
+
+        Ok(())
+    }
+
+    #[test]
+    fn encrypt_decrypt_round_trip() {
+        let key = [7u8; 32];
+        let plaintext = b"super secret shuffle bytes".to_vec();
+        let ciphertext = encrypt_block(&key, &plaintext).unwrap();
+        assert_ne!(&ciphertext[NONCE_LEN..], &plaintext[..]);
+        let decrypted = decrypt_block(&key, &ciphertext).unwrap();
+        assert_eq!(decrypted, plaintext);
+    }
+
+    #[test]
+    fn decrypt_rejects_tampered_ciphertext() {
+        let key = [7u8; 32];
+        let mut ciphertext = encrypt_block(&key, b"sensitive data").unwrap();
+        let last = ciphertext.len() - 1;
+        ciphertext[last] ^= 0xff;
+        assert_eq!(
+            decrypt_block(&key, &ciphertext).unwrap_err(),
+            ShuffleManagerError::DecryptionFailed
+        );
+    }
+
+    #[test]
+    fn tls_disabled_by_default() -> StdResult<(), Box<dyn std::error::Error + 'static>> {
+        assert!(ShuffleManager::load_tls_config()?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn bind_reuseport_shares_one_port_across_sockets() -> StdResult<(), Box<dyn std::error::Error + 'static>>
+    {
+        let port = get_free_port();
+        let addr = SocketAddr::from((Ipv4Addr::new(127, 0, 0, 1), port));
+        let listeners = bind_reuseport(addr, 4)?;
+        assert_eq!(listeners.len(), 4);
+        for listener in &listeners {
+            assert_eq!(listener.local_addr()?.port(), port);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn bind_reuseport_fails_against_a_non_reuseport_listener() {
+        let port = get_free_port();
+        let addr = SocketAddr::from((Ipv4Addr::new(127, 0, 0, 1), port));
+        // bind without SO_REUSEPORT first; the group bind must then fail as a whole
+        let _bind = TcpListener::bind(format!("127.0.0.1:{}", port)).unwrap();
+        assert!(bind_reuseport(addr, 2).is_err());
+    }
+
+    #[test]
+    fn registry_locate_prefers_same_host() {
+        let registry = LocationRegistry::default();
+        registry.merge(&BlocksAdvertisement {
+            server_uri: "http://10.0.0.2:7000".to_owned(),
+            blocks: vec![(1, 2, 3)],
+        });
+        registry.merge(&BlocksAdvertisement {
+            server_uri: "http://10.0.0.1:7000".to_owned(),
+            blocks: vec![(1, 2, 3)],
+        });
+        let peers = registry.locate((1, 2, 3), Duration::from_secs(60), "http://10.0.0.1:9000");
+        assert_eq!(peers, vec!["http://10.0.0.1:7000", "http://10.0.0.2:7000"]);
+    }
+
+    #[test]
+    fn registry_locate_expires_stale_entries() {
+        let registry = LocationRegistry::default();
+        registry.merge(&BlocksAdvertisement {
+            server_uri: "http://10.0.0.2:7000".to_owned(),
+            blocks: vec![(1, 2, 3)],
+        });
+        assert!(registry
+            .locate((1, 2, 3), Duration::from_millis(0), "http://10.0.0.1:9000")
+            .is_empty());
+    }
 }